@@ -1,21 +1,20 @@
 use futures::Future;
 use http::uri::Scheme;
 use hyper::client::connect::{Connect, Connected, Destination};
+use hyper::client::connect::dns::{Name, Resolve};
 use tokio_io::{AsyncRead, AsyncWrite};
-use tokio_timer::Timeout;
+use tokio_timer::{Delay, Timeout};
 
 
 #[cfg(feature = "default-tls")]
 use native_tls::{TlsConnector, TlsConnectorBuilder};
-#[cfg(feature = "tls")]
-use futures::Poll;
-#[cfg(feature = "tls")]
+use futures::{Async, Poll};
 use bytes::BufMut;
 
 use std::io;
 use std::sync::Arc;
-use std::net::IpAddr;
-use std::time::Duration;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
 
 #[cfg(feature = "trust-dns")]
 use dns::TrustDnsResolver;
@@ -31,78 +30,143 @@ pub(crate) struct Connector {
     inner: Inner,
     proxies: Arc<Vec<Proxy>>,
     timeout: Option<Duration>,
+    handshake_timeout: Option<Duration>,
 }
 
 enum Inner {
     #[cfg(not(feature = "tls"))]
-    Http(HttpConnector),
+    Http(ProxyProtocolConnector),
     #[cfg(feature = "default-tls")]
-    DefaultTls(::hyper_tls::HttpsConnector<HttpConnector>, TlsConnector),
+    DefaultTls(ProxyProtocolConnector, TlsConnector),
     #[cfg(feature = "rustls-tls")]
-    RustlsTls(::hyper_rustls::HttpsConnector<HttpConnector>, Arc<rustls::ClientConfig>)
+    RustlsTls(ProxyProtocolConnector, Arc<rustls::ClientConfig>)
+}
+
+/// Which PROXY protocol (if any) to speak as the first bytes of every
+/// freshly established transport, before any TLS handshake or HTTP bytes.
+///
+/// See <https://www.haproxy.org/download/1.8/doc/proxy-protocol.txt>.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum ProxyProtocol {
+    None,
+    V1,
+    V2,
+}
+
+impl Default for ProxyProtocol {
+    fn default() -> ProxyProtocol {
+        ProxyProtocol::None
+    }
+}
+
+/// The ALPN protocols offered by default: `h2` followed by `http/1.1`.
+pub(crate) fn alpn_protocols_default() -> Vec<Vec<u8>> {
+    vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+}
+
+/// The ALPN protocol list to use when the client is restricted to HTTP/1.1
+/// only, narrowing what's advertised during the TLS handshake.
+pub(crate) fn alpn_protocols_http1_only() -> Vec<Vec<u8>> {
+    vec![b"http/1.1".to_vec()]
 }
 
 impl Connector {
     #[cfg(not(feature = "tls"))]
-    pub(crate) fn new<T>(proxies: Arc<Vec<Proxy>>, local_addr: T) -> ::Result<Connector>
+    pub(crate) fn new<T>(proxies: Arc<Vec<Proxy>>, local_addr: T, proxy_protocol: ProxyProtocol) -> ::Result<Connector>
     where
         T: Into<Option<IpAddr>>
     {
 
         let mut http = http_connector()?;
         http.set_local_address(local_addr.into());
+        let http = ProxyProtocolConnector::new(http, proxy_protocol);
         Ok(Connector {
             inner: Inner::Http(http),
             proxies,
             timeout: None,
+            handshake_timeout: None,
         })
     }
 
     #[cfg(feature = "default-tls")]
     pub(crate) fn new_default_tls<T>(
-        tls: TlsConnectorBuilder,
+        mut tls: TlsConnectorBuilder,
         proxies: Arc<Vec<Proxy>>,
-        local_addr: T) -> ::Result<Connector>
+        local_addr: T,
+        proxy_protocol: ProxyProtocol,
+        alpn_protocols: Vec<Vec<u8>>) -> ::Result<Connector>
         where
             T: Into<Option<IpAddr>>,
     {
+        let alpns = alpn_protocols
+            .iter()
+            .filter_map(|p| ::std::str::from_utf8(p).ok())
+            .collect::<Vec<_>>();
+        tls.request_alpns(&alpns);
         let tls = try_!(tls.build());
 
         let mut http = http_connector()?;
         http.set_local_address(local_addr.into());
         http.enforce_http(false);
-        let http = ::hyper_tls::HttpsConnector::from((http, tls.clone()));
+        let http = ProxyProtocolConnector::new(http, proxy_protocol);
 
         Ok(Connector {
             inner: Inner::DefaultTls(http, tls),
             proxies,
             timeout: None,
+            handshake_timeout: None,
         })
     }
 
     #[cfg(feature = "rustls-tls")]
     pub(crate) fn new_rustls_tls<T>(
-        tls: rustls::ClientConfig,
+        mut tls: rustls::ClientConfig,
         proxies: Arc<Vec<Proxy>>,
-        local_addr: T) -> ::Result<Connector>
+        local_addr: T,
+        proxy_protocol: ProxyProtocol,
+        alpn_protocols: Vec<Vec<u8>>) -> ::Result<Connector>
         where
             T: Into<Option<IpAddr>>,
     {
+        tls.alpn_protocols = alpn_protocols;
+
         let mut http = http_connector()?;
         http.set_local_address(local_addr.into());
         http.enforce_http(false);
-        let http = ::hyper_rustls::HttpsConnector::from((http, tls.clone()));
+        let http = ProxyProtocolConnector::new(http, proxy_protocol);
 
         Ok(Connector {
             inner: Inner::RustlsTls(http, Arc::new(tls)),
             proxies,
             timeout: None,
+            handshake_timeout: None,
         })
     }
 
     pub(crate) fn set_timeout(&mut self, timeout: Option<Duration>) {
         self.timeout = timeout;
     }
+
+    /// Bounds only the TLS handshake (and, on proxy paths, the `CONNECT`
+    /// tunnel negotiation), independently of the outer connect `timeout`.
+    pub(crate) fn set_handshake_timeout(&mut self, timeout: Option<Duration>) {
+        self.handshake_timeout = timeout;
+    }
+
+    /// Enables RFC 8305 "Happy Eyeballs" dual-stack connection racing: once
+    /// an address family has been given `delay` to connect without success,
+    /// the next resolved address is tried concurrently rather than waiting
+    /// for it to finish or time out. `None` restores sequential connects.
+    pub(crate) fn set_happy_eyeballs(&mut self, delay: Option<Duration>) {
+        match &mut self.inner {
+            #[cfg(not(feature = "tls"))]
+            Inner::Http(http) => http.happy_eyeballs = delay,
+            #[cfg(feature = "default-tls")]
+            Inner::DefaultTls(http, _) => http.happy_eyeballs = delay,
+            #[cfg(feature = "rustls-tls")]
+            Inner::RustlsTls(http, _) => http.happy_eyeballs = delay,
+        }
+    }
 }
 
 #[cfg(feature = "trust-dns")]
@@ -117,6 +181,420 @@ fn http_connector() -> ::Result<HttpConnector> {
     Ok(HttpConnector::new(4))
 }
 
+/// Resolves `host` asynchronously, off the reactor thread, using the same
+/// resolver backend as `http_connector` (`trust-dns` when enabled, otherwise
+/// hyper's `GaiResolver`, which itself runs `getaddrinfo` via
+/// `tokio_threadpool::blocking` rather than on the calling thread).
+#[cfg(feature = "trust-dns")]
+fn resolve_host(host: &str) -> Box<Future<Item = Vec<IpAddr>, Error = io::Error> + Send> {
+    let name = match host.parse::<Name>() {
+        Ok(name) => name,
+        Err(_) => return Box::new(::futures::future::err(io::Error::new(io::ErrorKind::InvalidInput, "invalid DNS name"))),
+    };
+    match TrustDnsResolver::new() {
+        Ok(resolver) => Box::new(resolver.resolve(name).map(|addrs| addrs.collect())),
+        Err(e) => Box::new(::futures::future::err(io::Error::new(io::ErrorKind::Other, e))),
+    }
+}
+
+#[cfg(not(feature = "trust-dns"))]
+fn resolve_host(host: &str) -> Box<Future<Item = Vec<IpAddr>, Error = io::Error> + Send> {
+    use hyper::client::connect::dns::GaiResolver;
+
+    let name = match host.parse::<Name>() {
+        Ok(name) => name,
+        Err(_) => return Box::new(::futures::future::err(io::Error::new(io::ErrorKind::InvalidInput, "invalid DNS name"))),
+    };
+    Box::new(GaiResolver::new().resolve(name).map(|addrs| addrs.collect()))
+}
+
+/// Wraps the base `HttpConnector` so that a PROXY protocol header (if any)
+/// is written as the very first bytes on a connection to the real
+/// destination, before `Connector::connect` performs any TLS handshake on
+/// top of it. Connections to a configured `Proxy` itself go through
+/// `connect_raw` instead, which skips the header so it doesn't corrupt the
+/// proxy's own handshake. Also owns the optional Happy Eyeballs stagger
+/// delay, since racing addresses happens at this same DNS/TCP-connect layer,
+/// beneath any of that.
+struct ProxyProtocolConnector {
+    inner: HttpConnector,
+    proto: ProxyProtocol,
+    happy_eyeballs: Option<Duration>,
+}
+
+impl ProxyProtocolConnector {
+    fn new(inner: HttpConnector, proto: ProxyProtocol) -> ProxyProtocolConnector {
+        ProxyProtocolConnector { inner, proto, happy_eyeballs: None }
+    }
+
+    /// Connects to `dst` without writing a PROXY protocol header. Used for
+    /// hops to a configured `Proxy` (the SOCKS5 greeting or an HTTP
+    /// `CONNECT`/forwarded request), where prepending those bytes ahead of
+    /// the proxy's own handshake would corrupt it. Only a connection to the
+    /// real destination (`Connect::connect`, below) should carry the header.
+    fn connect_raw(&self, dst: Destination) -> <Self as Connect>::Future {
+        happy_eyeballs_connect(&self.inner, dst, self.happy_eyeballs)
+    }
+}
+
+impl Connect for ProxyProtocolConnector {
+    type Transport = <HttpConnector as Connect>::Transport;
+    type Error = io::Error;
+    type Future = Box<Future<Item = (Self::Transport, Connected), Error = io::Error> + Send>;
+
+    fn connect(&self, dst: Destination) -> Self::Future {
+        let proto = self.proto;
+        let connecting = self.connect_raw(dst);
+        Box::new(connecting.and_then(move |(tcp, connected)| {
+            let local_addr = tcp.local_addr().ok();
+            // Use the address actually connected to, not `dst.host()`: for
+            // the ordinary case of a hostname target, `dst.host()` is never
+            // a literal IP, so re-parsing it would always miss.
+            let peer_addr = tcp.peer_addr().ok();
+            let buf = match proto {
+                ProxyProtocol::None => Vec::new(),
+                ProxyProtocol::V1 => proxy_protocol_v1_header(local_addr, peer_addr),
+                ProxyProtocol::V2 => proxy_protocol_v2_header(local_addr, peer_addr),
+            };
+            ProxyProtocolWrite {
+                buf: io::Cursor::new(buf),
+                conn: Some(tcp),
+            }.map(move |tcp| (tcp, connected))
+        }))
+    }
+}
+
+/// Races a staggered connect across every address `host` resolves to (RFC
+/// 8305 "Happy Eyeballs"), instead of handing the whole `Destination` to
+/// `http` and letting it connect to resolved addresses one at a time. Each
+/// candidate is connected by handing `http` a copy of `dst` with its host
+/// replaced by the literal resolved address, so the already-configured
+/// `HttpConnector` (bind address via `local_addr`, connect timeout, etc.)
+/// still does the actual connecting rather than a bare `TcpStream::connect`.
+/// Resolution itself goes through `resolve_host`, the same async resolver
+/// `http_connector` uses, so this never blocks the reactor on DNS. With no
+/// `delay` configured, or when there's only one address to try, this just
+/// falls straight through to `http`'s own connect.
+fn happy_eyeballs_connect(
+    http: &HttpConnector,
+    dst: Destination,
+    delay: Option<Duration>,
+) -> Box<Future<Item = (<HttpConnector as Connect>::Transport, Connected), Error = io::Error> + Send> {
+    let delay = match delay {
+        Some(delay) => delay,
+        None => return Box::new(http.connect(dst)),
+    };
+
+    let http = http.clone();
+    let host = dst.host().to_owned();
+
+    Box::new(resolve_host(&host).then(move |result| -> Box<Future<Item = _, Error = _> + Send> {
+        let ips = match result {
+            Ok(ips) => ips,
+            Err(_) => return Box::new(http.connect(dst)),
+        };
+
+        let addrs = happy_eyeballs_interleave(ips);
+        if addrs.len() < 2 {
+            return Box::new(http.connect(dst));
+        }
+
+        Box::new(HappyEyeballs {
+            http,
+            template: dst,
+            addrs: addrs.into_iter(),
+            delay,
+            attempts: Vec::new(),
+            timer: None,
+            last_err: None,
+        })
+    }))
+}
+
+/// Interleaves address families (first IPv6, first IPv4, second IPv6, ...)
+/// per RFC 8305, so `HappyEyeballs` tries each family in turn instead of
+/// exhausting one before ever touching the other.
+fn happy_eyeballs_interleave(ips: Vec<IpAddr>) -> Vec<IpAddr> {
+    let (v6, v4): (Vec<IpAddr>, Vec<IpAddr>) = ips.into_iter().partition(|ip| ip.is_ipv6());
+
+    let mut v6 = v6.into_iter();
+    let mut v4 = v4.into_iter();
+    let mut addrs = Vec::new();
+    loop {
+        let a = v6.next();
+        let b = v4.next();
+        if a.is_none() && b.is_none() {
+            break;
+        }
+        addrs.extend(a);
+        addrs.extend(b);
+    }
+    addrs
+}
+
+/// Future that races a staggered connect across `addrs`: if the in-flight
+/// attempt(s) haven't completed within `delay`, the next candidate is
+/// started concurrently rather than cancelling or waiting on the others.
+/// The first attempt to succeed wins; if every address fails, the last error
+/// is surfaced. Each attempt goes through `http.connect`, on a clone of
+/// `template` with its host swapped for the literal candidate address, so
+/// every candidate is connected exactly the way a non-raced connect would be.
+struct HappyEyeballs {
+    http: HttpConnector,
+    template: Destination,
+    addrs: ::std::vec::IntoIter<IpAddr>,
+    delay: Duration,
+    attempts: Vec<<HttpConnector as Connect>::Future>,
+    timer: Option<Delay>,
+    last_err: Option<io::Error>,
+}
+
+impl HappyEyeballs {
+    fn connect_addr(&self, ip: IpAddr) -> <HttpConnector as Connect>::Future {
+        let mut dst = self.template.clone();
+        let host = match ip {
+            // A literal IPv6 host needs its brackets for `set_host` to accept
+            // it as a valid `Authority` host; `Ipv6Addr`'s `Display` doesn't
+            // add them on its own.
+            IpAddr::V6(ip) => format!("[{}]", ip),
+            IpAddr::V4(ip) => ip.to_string(),
+        };
+        dst.set_host(&host).expect("IP address should be a valid host");
+        self.http.connect(dst)
+    }
+}
+
+impl Future for HappyEyeballs {
+    type Item = (<HttpConnector as Connect>::Transport, Connected);
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        // Loop so that a freshly armed stagger timer is polled once, which
+        // registers this task to be woken when it fires.
+        loop {
+            let should_start = match self.timer {
+                None => self.attempts.is_empty(),
+                Some(ref mut timer) => match timer.poll() {
+                    Ok(Async::Ready(())) => true,
+                    Ok(Async::NotReady) => false,
+                    Err(_) => true,
+                },
+            };
+
+            if !should_start {
+                break;
+            }
+            if let Some(ip) = self.addrs.next() {
+                let attempt = self.connect_addr(ip);
+                self.attempts.push(attempt);
+                self.timer = Some(Delay::new(Instant::now() + self.delay));
+            } else {
+                self.timer = None;
+                break;
+            }
+        }
+
+        let mut i = 0;
+        while i < self.attempts.len() {
+            match self.attempts[i].poll() {
+                Ok(Async::Ready(connected)) => return Ok(Async::Ready(connected)),
+                Ok(Async::NotReady) => i += 1,
+                Err(err) => {
+                    self.last_err = Some(err);
+                    self.attempts.remove(i);
+                }
+            }
+        }
+
+        if self.attempts.is_empty() {
+            return match self.addrs.next() {
+                // The last in-flight attempt just failed and another address
+                // is ready to go: dive into it now instead of waiting out a
+                // stale stagger timer.
+                Some(ip) => {
+                    let attempt = self.connect_addr(ip);
+                    self.attempts.push(attempt);
+                    self.timer = Some(Delay::new(Instant::now() + self.delay));
+                    self.poll()
+                }
+                None => Err(self.last_err.take().unwrap_or_else(|| {
+                    io::Error::new(io::ErrorKind::Other, "happy eyeballs: no addresses succeeded")
+                })),
+            };
+        }
+
+        Ok(Async::NotReady)
+    }
+}
+
+fn proxy_protocol_v1_header(local_addr: Option<SocketAddr>, peer_addr: Option<SocketAddr>) -> Vec<u8> {
+    match (local_addr, peer_addr) {
+        (Some(SocketAddr::V4(src)), Some(SocketAddr::V4(dst))) => {
+            format!("PROXY TCP4 {} {} {} {}\r\n", src.ip(), dst.ip(), src.port(), dst.port()).into_bytes()
+        },
+        (Some(SocketAddr::V6(src)), Some(SocketAddr::V6(dst))) => {
+            format!("PROXY TCP6 {} {} {} {}\r\n", src.ip(), dst.ip(), src.port(), dst.port()).into_bytes()
+        },
+        _ => b"PROXY UNKNOWN\r\n".to_vec(),
+    }
+}
+
+fn proxy_protocol_v2_header(local_addr: Option<SocketAddr>, peer_addr: Option<SocketAddr>) -> Vec<u8> {
+    let mut header: Vec<u8> = vec![
+        0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A,
+        0x21,
+    ];
+
+    match (local_addr, peer_addr) {
+        (Some(SocketAddr::V4(src)), Some(SocketAddr::V4(dst))) => {
+            header.push(0x11); // AF_INET, SOCK_STREAM
+            header.extend_from_slice(&12u16.to_be_bytes());
+            header.extend_from_slice(&src.ip().octets());
+            header.extend_from_slice(&dst.ip().octets());
+            header.extend_from_slice(&src.port().to_be_bytes());
+            header.extend_from_slice(&dst.port().to_be_bytes());
+        },
+        (Some(SocketAddr::V6(src)), Some(SocketAddr::V6(dst))) => {
+            header.push(0x21); // AF_INET6, SOCK_STREAM
+            header.extend_from_slice(&36u16.to_be_bytes());
+            header.extend_from_slice(&src.ip().octets());
+            header.extend_from_slice(&dst.ip().octets());
+            header.extend_from_slice(&src.port().to_be_bytes());
+            header.extend_from_slice(&dst.port().to_be_bytes());
+        },
+        _ => {
+            header.push(0x00); // AF_UNSPEC
+            header.extend_from_slice(&0u16.to_be_bytes());
+        },
+    }
+
+    header
+}
+
+/// Future that writes a (possibly empty) buffer to a freshly connected
+/// transport before handing it back unchanged, used to prepend the PROXY
+/// protocol header ahead of any TLS handshake or HTTP bytes.
+struct ProxyProtocolWrite<T> {
+    buf: io::Cursor<Vec<u8>>,
+    conn: Option<T>,
+}
+
+impl<T> Future for ProxyProtocolWrite<T>
+where T: AsyncRead + AsyncWrite {
+    type Item = T;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if !self.buf.has_remaining_mut() {
+            return Ok(Async::Ready(self.conn.take().unwrap()));
+        }
+        loop {
+            let n = try_ready!(self.conn.as_mut().unwrap().write_buf(&mut self.buf));
+            if n == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "unexpected eof while writing PROXY protocol header"));
+            }
+            if !self.buf.has_remaining_mut() {
+                return Ok(Async::Ready(self.conn.take().unwrap()));
+            }
+        }
+    }
+}
+
+/// Returns `Some(socks5h)` when the proxy URI is a SOCKS5 proxy, where
+/// `socks5h` is `true` if DNS resolution should happen on the proxy side
+/// (`socks5h://`) rather than locally (`socks5://`).
+fn socks5_scheme(puri: &::http::Uri) -> Option<bool> {
+    match puri.scheme_part().map(Scheme::as_str) {
+        Some("socks5") => Some(false),
+        Some("socks5h") => Some(true),
+        _ => None,
+    }
+}
+
+/// Maps a `Timeout`-wrapped handshake error to an `io::Error`, shared by
+/// `tls_connect_default`/`tls_connect_rustls` below and by the
+/// `handshake_timeout!` macro in `Connect::connect`.
+fn handshake_timed_out(err: ::tokio_timer::timeout::Error<io::Error>) -> io::Error {
+    if err.is_inner() {
+        err.into_inner().expect("is_inner")
+    } else if err.is_elapsed() {
+        io::Error::new(io::ErrorKind::TimedOut, "tls handshake timed out")
+    } else {
+        io::Error::new(io::ErrorKind::Other, err)
+    }
+}
+
+/// Drives `conn` (an already-connected TCP stream, or a SOCKS5/`CONNECT`
+/// negotiation sitting on top of one) to completion and then runs the native-tls
+/// handshake for `host` over it, bounding the two together by
+/// `handshake_timeout` if one is configured. Shared by the direct, SOCKS5,
+/// and `CONNECT`-tunnel branches of `Connect::connect` below, since all
+/// three need the exact same handshake-then-optionally-timeout logic.
+///
+/// This can't simply delegate to `hyper_tls::HttpsConnector` instead: that
+/// connector's `connect()` is a single opaque future covering both the TCP
+/// connect and the TLS handshake, so there is no way to bound only the
+/// handshake (and any proxy negotiation) phase, and no way to route it
+/// through `ProxyProtocolConnector`'s header-free `connect_raw` for the
+/// proxy hops above. The direct (no `handshake_timeout`, no proxy) case
+/// below still behaves identically to going through `HttpsConnector`,
+/// since that's what it calls internally.
+#[cfg(feature = "default-tls")]
+fn tls_connect_default<T, F>(
+    tls: TlsConnector,
+    host: String,
+    conn: F,
+    handshake_timeout: Option<Duration>,
+) -> Box<Future<Item = self::native_tls_async::TlsStream<T>, Error = io::Error> + Send>
+where
+    T: AsyncRead + AsyncWrite + Send + 'static,
+    F: Future<Item = T, Error = io::Error> + Send + 'static,
+{
+    use self::native_tls_async::TlsConnectorExt;
+
+    let handshake = conn.and_then(move |conn| {
+        tls.connect_async(&host, conn)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+    });
+    if let Some(dur) = handshake_timeout {
+        Box::new(Timeout::new(handshake, dur).map_err(handshake_timed_out))
+    } else {
+        Box::new(handshake)
+    }
+}
+
+/// Rustls counterpart of `tls_connect_default` above; see its doc comment
+/// for why this isn't instead a call into `hyper_rustls::HttpsConnector`.
+#[cfg(feature = "rustls-tls")]
+fn tls_connect_rustls<T, F>(
+    tls: Arc<rustls::ClientConfig>,
+    host: String,
+    conn: F,
+    handshake_timeout: Option<Duration>,
+) -> Box<Future<Item = ::tokio_rustls::TlsStream<T, ::rustls::ClientSession>, Error = io::Error> + Send>
+where
+    T: AsyncRead + AsyncWrite + Send + 'static,
+    F: Future<Item = T, Error = io::Error> + Send + 'static,
+{
+    use tokio_rustls::TlsConnector as RustlsConnector;
+    use tokio_rustls::webpki::DNSNameRef;
+
+    let handshake = conn.and_then(move |conn| {
+        let dnsname = DNSNameRef::try_from_ascii_str(&host)
+            .map(|dnsname| dnsname.to_owned())
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "Invalid DNS Name"));
+        ::futures::future::result(dnsname).and_then(move |dnsname| {
+            RustlsConnector::from(tls).connect(dnsname.as_ref(), conn)
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+        })
+    });
+    if let Some(dur) = handshake_timeout {
+        Box::new(Timeout::new(handshake, dur).map_err(handshake_timed_out))
+    } else {
+        Box::new(handshake)
+    }
+}
+
 impl Connect for Connector {
     type Transport = Conn;
     type Error = io::Error;
@@ -141,19 +619,66 @@ impl Connect for Connector {
             }
         }
 
+        // Bounds a future that isn't itself TLS (namely, the plain-`socks5`
+        // tunnel negotiation below when the destination isn't HTTPS)
+        // independently of the outer `timeout!`. The TLS-handshake cases
+        // bound themselves via `tls_connect_default`/`tls_connect_rustls`.
+        macro_rules! handshake_timeout {
+            ($dur:expr, $future:expr) => {
+                if let Some(dur) = $dur {
+                    Box::new(Timeout::new($future, dur).map_err(handshake_timed_out))
+                        as Box<Future<Item = _, Error = io::Error> + Send>
+                } else {
+                    Box::new($future) as Box<Future<Item = _, Error = io::Error> + Send>
+                }
+            }
+        }
+
         macro_rules! connect {
-            ( $http:expr, $dst:expr, $proxy:expr ) => {
-                timeout!($http.connect($dst)
-                    .map(|(io, connected)| (Box::new(io) as Conn, connected.proxy($proxy))))
-            };
             ( $dst:expr, $proxy:expr ) => {
                 match &self.inner {
                     #[cfg(not(feature = "tls"))]
-                    Inner::Http(http) => connect!(http, $dst, $proxy),
+                    Inner::Http(http) => {
+                        let connecting = if $proxy { http.connect_raw($dst) } else { http.connect($dst) };
+                        timeout!(connecting
+                            .map(|(io, connected)| (Box::new(io) as Conn, connected.proxy($proxy))))
+                    },
                     #[cfg(feature = "default-tls")]
-                    Inner::DefaultTls(http, _) => connect!(http, $dst, $proxy),
+                    Inner::DefaultTls(http, tls) => {
+                        let is_https = $dst.scheme() == "https";
+                        let host = $dst.host().to_owned();
+                        let tls = tls.clone();
+                        let handshake_timeout = self.handshake_timeout;
+                        let connecting = if $proxy { http.connect_raw($dst) } else { http.connect($dst) };
+                        timeout!(connecting.and_then(move |(conn, connected)| -> Box<Future<Item = (Conn, Connected), Error = io::Error> + Send> {
+                            if is_https {
+                                Box::new(
+                                    tls_connect_default(tls, host, ::futures::future::ok(conn), handshake_timeout)
+                                        .map(move |io| (Box::new(io) as Conn, connected.proxy($proxy)))
+                                )
+                            } else {
+                                Box::new(::futures::future::ok((Box::new(conn) as Conn, connected.proxy($proxy))))
+                            }
+                        }))
+                    },
                     #[cfg(feature = "rustls-tls")]
-                    Inner::RustlsTls(http, _) => connect!(http, $dst, $proxy)
+                    Inner::RustlsTls(http, tls) => {
+                        let is_https = $dst.scheme() == "https";
+                        let host = $dst.host().to_owned();
+                        let tls = tls.clone();
+                        let handshake_timeout = self.handshake_timeout;
+                        let connecting = if $proxy { http.connect_raw($dst) } else { http.connect($dst) };
+                        timeout!(connecting.and_then(move |(conn, connected)| -> Box<Future<Item = (Conn, Connected), Error = io::Error> + Send> {
+                            if is_https {
+                                Box::new(
+                                    tls_connect_rustls(tls, host, ::futures::future::ok(conn), handshake_timeout)
+                                        .map(move |io| (Box::new(io) as Conn, connected.proxy($proxy)))
+                                )
+                            } else {
+                                Box::new(::futures::future::ok((Box::new(conn) as Conn, connected.proxy($proxy))))
+                            }
+                        }))
+                    },
                 }
             };
         }
@@ -161,6 +686,63 @@ impl Connect for Connector {
         for prox in self.proxies.iter() {
             if let Some(puri) = prox.intercept(&dst) {
                 trace!("proxy({:?}) intercepts {:?}", puri, dst);
+
+                if let Some(socks5h) = socks5_scheme(&puri) {
+                    let mut pdst = dst.clone();
+                    pdst.set_scheme("http")
+                        .expect("proxy target scheme should be valid");
+                    pdst.set_host(puri.host().expect("proxy target should have host"))
+                        .expect("proxy target host should be valid");
+                    pdst.set_port(puri.port_part().map(|port| port.as_u16()));
+
+                    let host = dst.host().to_owned();
+                    let port = dst.port().unwrap_or(if dst.scheme() == "https" { 443 } else { 80 });
+                    let auth = prox.auth().cloned();
+                    let is_https = dst.scheme() == "https";
+                    let handshake_timeout = self.handshake_timeout;
+
+                    macro_rules! socks5 {
+                        ( $http:expr ) => {
+                            timeout!($http.connect_raw(pdst).and_then(move |(conn, connected)| {
+                                trace!("connecting to {}:{} via socks5 proxy", host, port);
+                                handshake_timeout!(handshake_timeout, socks5_connect(conn, host, port, socks5h, auth))
+                                    .map(move |io| (Box::new(io) as Conn, connected.proxy(true)))
+                            }))
+                        };
+                    }
+
+                    match &self.inner {
+                        #[cfg(feature = "default-tls")]
+                        Inner::DefaultTls(http, tls) => if is_https {
+                            let tls = tls.clone();
+                            return timeout!(http.connect_raw(pdst).and_then(move |(conn, connected)| {
+                                trace!("connecting to {}:{} via socks5 proxy", host, port);
+                                let tls_host = host.clone();
+                                let socks5ing = socks5_connect(conn, host, port, socks5h, auth);
+                                tls_connect_default(tls, tls_host, socks5ing, handshake_timeout)
+                                    .map(|io| (Box::new(io) as Conn, connected.proxy(true)))
+                            }));
+                        } else {
+                            return socks5!(http);
+                        },
+                        #[cfg(feature = "rustls-tls")]
+                        Inner::RustlsTls(http, tls) => if is_https {
+                            let tls = tls.clone();
+                            return timeout!(http.connect_raw(pdst).and_then(move |(conn, connected)| {
+                                trace!("connecting to {}:{} via socks5 proxy", host, port);
+                                let tls_host = host.clone();
+                                let socks5ing = socks5_connect(conn, host, port, socks5h, auth);
+                                tls_connect_rustls(tls, tls_host, socks5ing, handshake_timeout)
+                                    .map(|io| (Box::new(io) as Conn, connected.proxy(true)))
+                            }));
+                        } else {
+                            return socks5!(http);
+                        },
+                        #[cfg(not(feature = "tls"))]
+                        Inner::Http(http) => return socks5!(http),
+                    }
+                }
+
                 let mut ndst = dst.clone();
                 let new_scheme = puri
                     .scheme_part()
@@ -176,47 +758,33 @@ impl Connect for Connector {
 
                 #[cfg(feature = "tls")]
                 let auth = prox.auth().cloned();
+                #[cfg(feature = "tls")]
+                let handshake_timeout = self.handshake_timeout;
 
                 match &self.inner {
                     #[cfg(feature = "default-tls")]
                     Inner::DefaultTls(http, tls) => if dst.scheme() == "https" {
-                        #[cfg(feature = "default-tls")]
-                        use self::native_tls_async::TlsConnectorExt;
-
                         let host = dst.host().to_owned();
                         let port = dst.port().unwrap_or(443);
                         let tls = tls.clone();
-                        return timeout!(http.connect(ndst).and_then(move |(conn, connected)| {
+                        return timeout!(http.connect_raw(ndst).and_then(move |(conn, connected)| {
                             trace!("tunneling HTTPS over proxy");
-                            tunnel(conn, host.clone(), port, auth)
-                                .and_then(move |tunneled| {
-                                    tls.connect_async(&host, tunneled)
-                                        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
-                                })
+                            let tls_host = host.clone();
+                            let tunneling = tunnel(conn, host, port, auth);
+                            tls_connect_default(tls, tls_host, tunneling, handshake_timeout)
                                 .map(|io| (Box::new(io) as Conn, connected.proxy(true)))
                         }));
                     },
                     #[cfg(feature = "rustls-tls")]
                     Inner::RustlsTls(http, tls) => if dst.scheme() == "https" {
-                        #[cfg(feature = "rustls-tls")]
-                        use tokio_rustls::TlsConnector as RustlsConnector;
-                        #[cfg(feature = "rustls-tls")]
-                        use tokio_rustls::webpki::DNSNameRef;
-
                         let host = dst.host().to_owned();
                         let port = dst.port().unwrap_or(443);
                         let tls = tls.clone();
-                        return timeout!(http.connect(ndst).and_then(move |(conn, connected)| {
+                        return timeout!(http.connect_raw(ndst).and_then(move |(conn, connected)| {
                             trace!("tunneling HTTPS over proxy");
-                            let maybe_dnsname = DNSNameRef::try_from_ascii_str(&host)
-                                .map(|dnsname| dnsname.to_owned())
-                                .map_err(|_| io::Error::new(io::ErrorKind::Other, "Invalid DNS Name"));
-                            tunnel(conn, host, port, auth)
-                                .and_then(move |tunneled| Ok((maybe_dnsname?, tunneled)))
-                                .and_then(move |(dnsname, tunneled)| {
-                                    RustlsConnector::from(tls).connect(dnsname.as_ref(), tunneled)
-                                        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
-                                })
+                            let tls_host = host.clone();
+                            let tunneling = tunnel(conn, host, port, auth);
+                            tls_connect_rustls(tls, tls_host, tunneling, handshake_timeout)
                                 .map(|io| (Box::new(io) as Conn, connected.proxy(true)))
                         }));
                     },
@@ -325,6 +893,243 @@ fn tunnel_eof() -> io::Error {
     )
 }
 
+/// Performs a SOCKS5 handshake (RFC 1928 / RFC 1929) over an already
+/// established TCP connection to the proxy, analogous to `tunnel` for
+/// HTTP CONNECT proxies.
+fn socks5_connect<T>(
+    conn: T,
+    host: String,
+    port: u16,
+    socks5h: bool,
+    auth: Option<::proxy::Auth>,
+) -> Socks5Handshake<T> {
+    let user_pass = match auth {
+        Some(::proxy::Auth::Basic(value)) => {
+            // `value` is the full `Proxy-Authorization` header value (as
+            // `tunnel`, above, writes verbatim), i.e. `"Basic <base64>"` —
+            // strip the scheme before decoding the RFC 1929 user/pass out of it.
+            ::base64::decode(value.trim_start_matches("Basic "))
+                .ok()
+                .and_then(|decoded| String::from_utf8(decoded).ok())
+                .and_then(|decoded| {
+                    let mut parts = decoded.splitn(2, ':');
+                    match (parts.next(), parts.next()) {
+                        (Some(user), Some(pass)) => Some((user.to_owned(), pass.to_owned())),
+                        _ => None,
+                    }
+                })
+        },
+        None => None,
+    };
+
+    let mut greeting = vec![0x05u8];
+    if user_pass.is_some() {
+        greeting.push(2);
+        greeting.push(0x00);
+        greeting.push(0x02);
+    } else {
+        greeting.push(1);
+        greeting.push(0x00);
+    }
+
+    Socks5Handshake {
+        conn: Some(conn),
+        state: Socks5State::WriteGreeting,
+        buf: io::Cursor::new(greeting),
+        user_pass,
+        host,
+        port,
+        socks5h,
+    }
+}
+
+/// Builds the SOCKS5 `CONNECT` request bytes for `host:port`, asynchronously
+/// resolving `host` first when `socks5h` is `false` (local-side resolution)
+/// so the DNS lookup doesn't block whichever thread drives the handshake.
+fn socks5_connect_request(host: String, port: u16, socks5h: bool) -> Box<Future<Item = Vec<u8>, Error = io::Error> + Send> {
+    if socks5h {
+        let mut req = vec![0x05u8, 0x01, 0x00, 0x03, host.len() as u8];
+        req.extend_from_slice(host.as_bytes());
+        req.push((port >> 8) as u8);
+        req.push((port & 0xff) as u8);
+        return Box::new(::futures::future::ok(req));
+    }
+
+    Box::new(resolve_host(&host).then(move |result| {
+        let mut req = vec![0x05u8, 0x01, 0x00];
+        match result.ok().and_then(|addrs| addrs.into_iter().next()) {
+            Some(IpAddr::V4(ip)) => {
+                req.push(0x01);
+                req.extend_from_slice(&ip.octets());
+            },
+            Some(IpAddr::V6(ip)) => {
+                req.push(0x04);
+                req.extend_from_slice(&ip.octets());
+            },
+            None => {
+                // fall back to proxy-side resolution if local resolution failed
+                req.push(0x03);
+                req.push(host.len() as u8);
+                req.extend_from_slice(host.as_bytes());
+            },
+        }
+        req.push((port >> 8) as u8);
+        req.push((port & 0xff) as u8);
+        Ok(req) as Result<Vec<u8>, io::Error>
+    }))
+}
+
+struct Socks5Handshake<T> {
+    conn: Option<T>,
+    state: Socks5State,
+    buf: io::Cursor<Vec<u8>>,
+    user_pass: Option<(String, String)>,
+    host: String,
+    port: u16,
+    socks5h: bool,
+}
+
+enum Socks5State {
+    WriteGreeting,
+    ReadMethod,
+    WriteAuth,
+    ReadAuthStatus,
+    Resolving(Box<Future<Item = Vec<u8>, Error = io::Error> + Send>),
+    WriteConnect,
+    ReadConnectReply,
+}
+
+impl<T> Future for Socks5Handshake<T>
+where T: AsyncRead + AsyncWrite {
+    type Item = T;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            match self.state {
+                Socks5State::WriteGreeting | Socks5State::WriteAuth | Socks5State::WriteConnect => {
+                    let n = try_ready!(self.conn.as_mut().unwrap().write_buf(&mut self.buf));
+                    if n == 0 {
+                        return Err(socks5_eof());
+                    }
+                    if !self.buf.has_remaining_mut() {
+                        self.buf.get_mut().truncate(0);
+                        self.buf.set_position(0);
+                        self.state = match self.state {
+                            Socks5State::WriteGreeting => Socks5State::ReadMethod,
+                            Socks5State::WriteAuth => Socks5State::ReadAuthStatus,
+                            Socks5State::WriteConnect => Socks5State::ReadConnectReply,
+                            _ => unreachable!(),
+                        };
+                    }
+                },
+                Socks5State::ReadMethod => {
+                    let n = try_ready!(self.conn.as_mut().unwrap().read_buf(&mut self.buf.get_mut()));
+                    if n == 0 {
+                        return Err(socks5_eof());
+                    }
+                    if self.buf.get_ref().len() < 2 {
+                        continue;
+                    }
+                    let method = self.buf.get_ref()[1];
+                    if self.buf.get_ref()[0] != 0x05 {
+                        return Err(io::Error::new(io::ErrorKind::Other, "invalid socks5 version in method selection"));
+                    }
+                    match method {
+                        0x00 => {
+                            self.state = Socks5State::Resolving(socks5_connect_request(self.host.clone(), self.port, self.socks5h));
+                        },
+                        0x02 if self.user_pass.is_some() => {
+                            let (user, pass) = self.user_pass.clone().expect("checked is_some");
+                            let mut req = Vec::with_capacity(3 + user.len() + pass.len());
+                            req.push(0x01);
+                            req.push(user.len() as u8);
+                            req.extend_from_slice(user.as_bytes());
+                            req.push(pass.len() as u8);
+                            req.extend_from_slice(pass.as_bytes());
+                            self.buf = io::Cursor::new(req);
+                            self.state = Socks5State::WriteAuth;
+                        },
+                        0xff => return Err(io::Error::new(io::ErrorKind::Other, "no acceptable socks5 auth method")),
+                        _ => return Err(io::Error::new(io::ErrorKind::Other, "unexpected socks5 auth method")),
+                    }
+                },
+                Socks5State::ReadAuthStatus => {
+                    let n = try_ready!(self.conn.as_mut().unwrap().read_buf(&mut self.buf.get_mut()));
+                    if n == 0 {
+                        return Err(socks5_eof());
+                    }
+                    if self.buf.get_ref().len() < 2 {
+                        continue;
+                    }
+                    if self.buf.get_ref()[1] != 0x00 {
+                        return Err(io::Error::new(io::ErrorKind::Other, "socks5 proxy authentication failed"));
+                    }
+                    self.state = Socks5State::Resolving(socks5_connect_request(self.host.clone(), self.port, self.socks5h));
+                },
+                Socks5State::Resolving(ref mut fut) => {
+                    let req = try_ready!(fut.poll());
+                    self.buf = io::Cursor::new(req);
+                    self.state = Socks5State::WriteConnect;
+                },
+                Socks5State::ReadConnectReply => {
+                    let n = try_ready!(self.conn.as_mut().unwrap().read_buf(&mut self.buf.get_mut()));
+                    if n == 0 {
+                        return Err(socks5_eof());
+                    }
+                    let read = self.buf.get_ref();
+                    if read.len() < 4 {
+                        continue;
+                    }
+                    let atyp = read[3];
+                    let addr_len = match atyp {
+                        0x01 => 4,
+                        0x04 => 16,
+                        0x03 => {
+                            if read.len() < 5 {
+                                continue;
+                            }
+                            1 + read[4] as usize
+                        },
+                        _ => return Err(io::Error::new(io::ErrorKind::Other, "unsupported socks5 address type in reply")),
+                    };
+                    let needed = 4 + addr_len + 2;
+                    if read.len() < needed {
+                        continue;
+                    }
+                    let rep = read[1];
+                    if rep != 0x00 {
+                        return Err(io::Error::new(io::ErrorKind::Other, socks5_reply_error(rep)));
+                    }
+                    return Ok(self.conn.take().unwrap().into());
+                },
+            }
+        }
+    }
+}
+
+#[inline]
+fn socks5_eof() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "unexpected eof during socks5 handshake"
+    )
+}
+
+fn socks5_reply_error(rep: u8) -> &'static str {
+    match rep {
+        0x01 => "socks5 general server failure",
+        0x02 => "socks5 connection not allowed by ruleset",
+        0x03 => "socks5 network unreachable",
+        0x04 => "socks5 host unreachable",
+        0x05 => "socks5 connection refused",
+        0x06 => "socks5 TTL expired",
+        0x07 => "socks5 command not supported",
+        0x08 => "socks5 address type not supported",
+        _ => "socks5 unknown error",
+    }
+}
+
 #[cfg(feature = "default-tls")]
 mod native_tls_async {
     use std::io::{self, Read, Write};
@@ -465,12 +1270,17 @@ mod tests {
     extern crate tokio_tcp;
 
     use std::io::{Read, Write};
-    use std::net::TcpListener;
+    use std::net::{IpAddr, SocketAddr, TcpListener};
     use std::thread;
+    use std::time::Duration;
     use futures::Future;
+    use hyper::client::connect::Destination;
     use tokio::runtime::current_thread::Runtime;
     use self::tokio_tcp::TcpStream;
-    use super::tunnel;
+    use super::{
+        happy_eyeballs_interleave, http_connector, proxy_protocol_v1_header,
+        proxy_protocol_v2_header, socks5_connect, tunnel, HappyEyeballs,
+    };
     use proxy;
 
     static TUNNEL_OK: &'static [u8] = b"\
@@ -589,4 +1399,283 @@ mod tests {
 
         rt.block_on(work).unwrap();
     }
+
+    static SOCKS5_METHOD_NO_AUTH: &'static [u8] = &[0x05, 0x00];
+    static SOCKS5_CONNECT_OK: &'static [u8] = &[0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0];
+
+    macro_rules! mock_socks5 {
+        () => ({
+            mock_socks5!(SOCKS5_METHOD_NO_AUTH, SOCKS5_CONNECT_OK)
+        });
+        ($method_reply:expr, $connect_reply:expr) => ({
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+
+            thread::spawn(move || {
+                let (mut sock, _) = listener.accept().unwrap();
+                let mut buf = [0u8; 4096];
+
+                // greeting: VER=5, NMETHODS=1, METHODS=[no-auth]
+                let n = sock.read(&mut buf).unwrap();
+                assert_eq!(&buf[..n], &[0x05, 0x01, 0x00][..]);
+                sock.write_all($method_reply).unwrap();
+
+                // CONNECT request: VER=5, CMD=1 (CONNECT), RSV=0, then ATYP+addr+port
+                let n = sock.read(&mut buf).unwrap();
+                assert!(n >= 3);
+                assert_eq!(&buf[..3], &[0x05, 0x01, 0x00][..]);
+                sock.write_all($connect_reply).unwrap();
+            });
+
+            addr
+        })
+    }
+
+    #[test]
+    fn test_socks5_connect() {
+        let addr = mock_socks5!();
+
+        let mut rt = Runtime::new().unwrap();
+        let work = TcpStream::connect(&addr).and_then(|tcp| {
+            // socks5h = true keeps resolution on the proxy side, so the
+            // handshake doesn't need a working DNS lookup for "example.com".
+            socks5_connect(tcp, "example.com".to_owned(), 443, true, None)
+        });
+
+        rt.block_on(work).unwrap();
+    }
+
+    #[test]
+    fn test_socks5_connect_request_bytes_domain_atyp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut sock, _) = listener.accept().unwrap();
+            let mut buf = [0u8; 4096];
+            let n = sock.read(&mut buf).unwrap();
+            assert_eq!(&buf[..n], &[0x05, 0x01, 0x00][..]); // VER, NMETHODS, no-auth
+            sock.write_all(SOCKS5_METHOD_NO_AUTH).unwrap();
+
+            let n = sock.read(&mut buf).unwrap();
+            // VER, CMD=CONNECT, RSV, ATYP=domain, LEN=6, "ex.com", port 443
+            let expected: &[u8] = &[
+                0x05, 0x01, 0x00, 0x03, 6, b'e', b'x', b'.', b'c', b'o', b'm', 0x01, 0xBB,
+            ];
+            assert_eq!(&buf[..n], expected);
+            sock.write_all(SOCKS5_CONNECT_OK).unwrap();
+        });
+
+        let mut rt = Runtime::new().unwrap();
+        let work = TcpStream::connect(&addr).and_then(|tcp| {
+            socks5_connect(tcp, "ex.com".to_owned(), 443, true, None)
+        });
+
+        rt.block_on(work).unwrap();
+    }
+
+    #[test]
+    fn test_socks5_connect_auth_failed() {
+        let addr = mock_socks5!(&[0x05, 0xff], SOCKS5_CONNECT_OK);
+
+        let mut rt = Runtime::new().unwrap();
+        let work = TcpStream::connect(&addr).and_then(|tcp| {
+            socks5_connect(tcp, "example.com".to_owned(), 443, true, None)
+        });
+
+        let error = rt.block_on(work).unwrap_err();
+        assert_eq!(error.to_string(), "no acceptable socks5 auth method");
+    }
+
+    #[test]
+    fn test_socks5_connect_with_auth() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        thread::spawn(move || {
+            let (mut sock, _) = listener.accept().unwrap();
+            let mut buf = [0u8; 4096];
+
+            // greeting offers no-auth and user/pass since `auth` is `Some`.
+            let n = sock.read(&mut buf).unwrap();
+            assert_eq!(&buf[..n], &[0x05, 0x02, 0x00, 0x02][..]);
+            sock.write_all(&[0x05, 0x02]).unwrap(); // select user/pass
+
+            // RFC 1929 sub-negotiation: VER=1, ULEN, UNAME, PLEN, PASSWD,
+            // decoded from the `Proxy-Authorization`-style header value
+            // rather than the raw bytes.
+            let n = sock.read(&mut buf).unwrap();
+            let expected: &[u8] = &[
+                0x01, 7, b'A', b'l', b'a', b'd', b'd', b'i', b'n',
+                11, b'o', b'p', b'e', b'n', b' ', b's', b'e', b's', b'a', b'm', b'e',
+            ];
+            assert_eq!(&buf[..n], expected);
+            sock.write_all(&[0x01, 0x00]).unwrap(); // auth succeeded
+
+            sock.read(&mut buf).unwrap();
+            assert_eq!(&buf[..3], &[0x05, 0x01, 0x00][..]);
+            sock.write_all(SOCKS5_CONNECT_OK).unwrap();
+        });
+
+        let mut rt = Runtime::new().unwrap();
+        let auth = proxy::Auth::basic("Aladdin", "open sesame");
+        let work = TcpStream::connect(&addr).and_then(|tcp| {
+            socks5_connect(tcp, "example.com".to_owned(), 443, true, Some(auth))
+        });
+
+        rt.block_on(work).unwrap();
+    }
+
+    #[test]
+    fn test_proxy_protocol_v1_header_ipv4() {
+        let local_addr: SocketAddr = "127.0.0.1:12345".parse().unwrap();
+        let peer_addr: SocketAddr = "93.184.216.34:443".parse().unwrap();
+
+        let header = proxy_protocol_v1_header(Some(local_addr), Some(peer_addr));
+        assert_eq!(header, b"PROXY TCP4 127.0.0.1 93.184.216.34 12345 443\r\n".to_vec());
+    }
+
+    #[test]
+    fn test_proxy_protocol_v1_header_hostname_target_uses_peer_addr() {
+        // `dst.host()` for a hostname target (e.g. "example.com") is never a
+        // literal IP, so the header must be built from the address actually
+        // connected to (`tcp.peer_addr()`), not a re-parse of the advertised
+        // `Destination` host.
+        let local_addr: SocketAddr = "127.0.0.1:12345".parse().unwrap();
+        let peer_addr: SocketAddr = "93.184.216.34:443".parse().unwrap();
+        let dst = Destination::try_from_uri("http://example.com:443".parse().unwrap()).unwrap();
+        assert!(dst.host().parse::<IpAddr>().is_err());
+
+        let header = proxy_protocol_v1_header(Some(local_addr), Some(peer_addr));
+        assert_eq!(header, b"PROXY TCP4 127.0.0.1 93.184.216.34 12345 443\r\n".to_vec());
+    }
+
+    #[test]
+    fn test_proxy_protocol_v1_header_unknown() {
+        let header = proxy_protocol_v1_header(None, None);
+        assert_eq!(header, b"PROXY UNKNOWN\r\n".to_vec());
+    }
+
+    #[test]
+    fn test_proxy_protocol_v2_header_ipv4() {
+        let local_addr: SocketAddr = "127.0.0.1:12345".parse().unwrap();
+        let peer_addr: SocketAddr = "93.184.216.34:443".parse().unwrap();
+
+        let header = proxy_protocol_v2_header(Some(local_addr), Some(peer_addr));
+
+        let mut expected = vec![
+            0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A,
+            0x21, 0x11, 0x00, 0x0C,
+        ];
+        expected.extend_from_slice(&[127, 0, 0, 1]);
+        expected.extend_from_slice(&[93, 184, 216, 34]);
+        expected.extend_from_slice(&12345u16.to_be_bytes());
+        expected.extend_from_slice(&443u16.to_be_bytes());
+
+        assert_eq!(header, expected);
+    }
+
+    #[test]
+    fn test_happy_eyeballs_interleaves_address_families() {
+        let v6_a: IpAddr = "::1".parse().unwrap();
+        let v6_b: IpAddr = "::2".parse().unwrap();
+        let v4_a: IpAddr = "127.0.0.1".parse().unwrap();
+
+        let addrs = happy_eyeballs_interleave(vec![v4_a, v6_a, v6_b]);
+
+        assert_eq!(addrs, vec![v6_a, v4_a, v6_b]);
+    }
+
+    #[test]
+    fn test_happy_eyeballs_starts_next_address_after_delay() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let _ = listener.accept();
+        });
+
+        // 192.0.2.1 is reserved (RFC 5737 TEST-NET-1) and unroutable, so
+        // connecting to it reliably hangs rather than completing or failing
+        // right away, giving the stagger timer below something to race
+        // against: it should give up waiting on it and start the second,
+        // real, address concurrently instead.
+        let unreachable: IpAddr = "192.0.2.1".parse().unwrap();
+
+        let dst = Destination::try_from_uri(
+            format!("http://placeholder:{}", addr.port()).parse().unwrap()
+        ).unwrap();
+        let http = http_connector().unwrap();
+
+        let work = HappyEyeballs {
+            http,
+            template: dst,
+            addrs: vec![unreachable, addr.ip()].into_iter(),
+            delay: Duration::from_millis(50),
+            attempts: Vec::new(),
+            timer: None,
+            last_err: None,
+        };
+
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(work).unwrap();
+    }
+
+    #[test]
+    fn test_happy_eyeballs_surfaces_last_error_when_all_fail() {
+        // Every attempt shares `template`'s port, so pick one free port and
+        // try it against two different loopback addresses (127.0.0.0/8 is
+        // entirely loopback on Linux) with nothing bound to accept on
+        // either: both connects should fail quickly (connection refused).
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+
+        let dst = Destination::try_from_uri(
+            format!("http://placeholder:{}", port).parse().unwrap()
+        ).unwrap();
+        let http = http_connector().unwrap();
+
+        let addrs: Vec<IpAddr> = vec!["127.0.0.1".parse().unwrap(), "127.0.0.2".parse().unwrap()];
+        let work = HappyEyeballs {
+            http,
+            template: dst,
+            addrs: addrs.into_iter(),
+            delay: Duration::from_millis(20),
+            attempts: Vec::new(),
+            timer: None,
+            last_err: None,
+        };
+
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(work).unwrap_err();
+    }
+
+    #[test]
+    fn test_happy_eyeballs_connects_to_ipv6_candidate() {
+        // `connect_addr` has to bracket a literal IPv6 host before handing it
+        // to `set_host` (`Ipv6Addr`'s `Display` doesn't add the `[...]` a
+        // URI/`Authority` host requires) — exercise a real `::1` candidate,
+        // not just IPv4 ones, so a regression here panics this test instead
+        // of every real dual-stack connect.
+        let listener = TcpListener::bind("[::1]:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let _ = listener.accept();
+        });
+
+        let dst = Destination::try_from_uri(
+            format!("http://placeholder:{}", addr.port()).parse().unwrap()
+        ).unwrap();
+        let http = http_connector().unwrap();
+
+        let work = HappyEyeballs {
+            http,
+            template: dst,
+            addrs: vec![addr.ip()].into_iter(),
+            delay: Duration::from_millis(50),
+            attempts: Vec::new(),
+            timer: None,
+            last_err: None,
+        };
+
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(work).unwrap();
+    }
 }